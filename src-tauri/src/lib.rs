@@ -1,14 +1,18 @@
 // src-lib.rs
 
 // --- use文 (ファイルの先頭に追加) ---
-use encoding_rs::{SHIFT_JIS, UTF_8};
+use encoding_rs::{EUC_JP, ISO_2022_JP, SHIFT_JIS, UTF_16BE, UTF_16LE, UTF_8};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tauri::{AppHandle, Emitter, Manager, WindowEvent, State};
 use tauri_plugin_dialog::DialogExt;
 use tauri_plugin_window_state::{Builder, StateFlags};
 use tauri_plugin_cli::CliExt;
+use tauri_plugin_store::StoreExt;
 
 // --- FileEntry構造体の定義 ---
 #[derive(serde::Serialize, Clone)] // Cloneを追加すると後で便利
@@ -16,6 +20,22 @@ struct FileEntry {
     name: String,
     path: PathBuf,
     is_dir: bool,
+    // ファイルサイズ(バイト)。ディレクトリの場合はメタデータ依存で当てにならない
+    size: u64,
+    is_symlink: bool,
+    // タイムスタンプはunix millis。プラットフォームが未対応なら None
+    created: Option<u64>,
+    modified: Option<u64>,
+    accessed: Option<u64>,
+    // ディレクトリの場合のみ、直下の項目数 (read_dir().count())
+    child_count: Option<usize>,
+}
+
+// SystemTime を unix millis に変換する。取得できない場合は None
+fn to_unix_millis(time: std::io::Result<SystemTime>) -> Option<u64> {
+    time.ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64)
 }
 #[derive(serde::Serialize)]
 struct FileData {
@@ -23,6 +43,198 @@ struct FileData {
     encoding: String,
     line_ending: String,
 }
+// read_file_range が返すウィンドウ。仮想スクロール用に総行数とバイト位置も添える
+#[derive(serde::Serialize)]
+struct FileRangeData {
+    content: String,
+    encoding: String,
+    line_ending: String,
+    // ファイル全体の行数 (末尾に改行が無い最終行も1行として数える)
+    total_lines: usize,
+    // 返したウィンドウ先頭のファイル内バイトオフセット
+    byte_offset: u64,
+}
+
+// バイト列を read_file と同じ判定ラダーでデコードし、(content, encodingラベル) を返す。
+// UTF-16(BOM) → UTF-8(BOM) → UTF-8 → レガシー日本語(EUC-JP / ISO-2022-JP / Shift_JIS)。
+// read_file と read_file_range で共有し、全文読みとウィンドウ読みの判定を一致させる。
+fn decode_with_ladder(bytes: &[u8]) -> Result<(String, String), String> {
+    // UTF-16 のBOMチェック (UTF-8より先に見る)
+    if bytes.starts_with(&[0xFF, 0xFE]) {
+        let (cow, _used, _had_errors) = UTF_16LE.decode(bytes);
+        return Ok((cow.into_owned(), "UTF-16LE".to_string()));
+    }
+    if bytes.starts_with(&[0xFE, 0xFF]) {
+        let (cow, _used, _had_errors) = UTF_16BE.decode(bytes);
+        return Ok((cow.into_owned(), "UTF-16BE".to_string()));
+    }
+    // BOM付きUTF-8
+    if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        let content = std::str::from_utf8(&bytes[3..]).map_err(|e| e.to_string())?.to_string();
+        return Ok((content, "UTF-8".to_string()));
+    }
+    // BOMなしUTF-8
+    let (cow, _encoding_used, had_errors) = UTF_8.decode(bytes);
+    if !had_errors {
+        return Ok((cow.into_owned(), "UTF-8".to_string()));
+    }
+    // レガシー日本語エンコーディングの候補を順に試す。Windows-31J は Shift_JIS と同一。
+    const LEGACY_CANDIDATES: [(&encoding_rs::Encoding, &str); 3] = [
+        (EUC_JP, "EUC-JP"),
+        (ISO_2022_JP, "ISO-2022-JP"),
+        (SHIFT_JIS, "Shift_JIS"),
+    ];
+    for (encoding, label) in LEGACY_CANDIDATES {
+        let (cow, _used, had_errors) = encoding.decode(bytes);
+        if !had_errors {
+            return Ok((cow.into_owned(), label.to_string()));
+        }
+    }
+    Err("Unsupported encoding detected. MirrorShard only supports UTF-8, UTF-16, and common Japanese encodings.".to_string())
+}
+
+// --- ファイルアクセスを制限するためのスコープ ---
+// 許可されたルートディレクトリ群と、任意のglob許可/拒否パターンを保持する。
+// Tauriのasset-protocolスコープと同じく「開いたフォルダだけ触れる」挙動にする。
+#[derive(Default)]
+struct FsScopeInner {
+    roots: Vec<PathBuf>,
+    allow: Vec<glob::Pattern>,
+    deny: Vec<glob::Pattern>,
+}
+struct FsScope(Mutex<FsScopeInner>);
+
+// スコープ違反時に返す共通エラー文言
+const NOT_PERMITTED: &str = "path not permitted";
+
+// 引数のパスを正規化し、許可ルート内に収まっているか検証して正規化後のパスを返す。
+// `..` やシンボリックリンクによる脱出も canonicalize で潰す。
+// `must_exist` が false でパスがまだ存在しない場合(新規ファイルの書き込み等)は親ディレクトリを基準に検証する。
+fn resolve_in_scope(scope: &FsScope, path: &str, must_exist: bool) -> Result<PathBuf, String> {
+    let inner = scope.0.lock().unwrap();
+
+    let canonical = if must_exist {
+        std::fs::canonicalize(path).map_err(|_| NOT_PERMITTED.to_string())?
+    } else if let Ok(existing) = std::fs::canonicalize(path) {
+        // 既存のパスはシンボリックリンクも含めて実体まで正規化する
+        existing
+    } else {
+        // まだ存在しないファイルは親ディレクトリを正規化してから名前を足す
+        let p = PathBuf::from(path);
+        let parent = p.parent().ok_or_else(|| NOT_PERMITTED.to_string())?;
+        let file_name = p.file_name().ok_or_else(|| NOT_PERMITTED.to_string())?;
+        let parent = std::fs::canonicalize(parent).map_err(|_| NOT_PERMITTED.to_string())?;
+        parent.join(file_name)
+    };
+
+    // 許可ルートのいずれかに属していること
+    let in_root = inner.roots.iter().any(|root| {
+        std::fs::canonicalize(root)
+            .map(|r| canonical.starts_with(&r))
+            .unwrap_or(false)
+    });
+    if !in_root {
+        return Err(NOT_PERMITTED.to_string());
+    }
+
+    let path_str = canonical.to_string_lossy();
+    // 拒否パターンが優先
+    if inner.deny.iter().any(|p| p.matches(&path_str)) {
+        return Err(NOT_PERMITTED.to_string());
+    }
+    // 許可パターンがあるなら、そのいずれかに一致すること
+    if !inner.allow.is_empty() && !inner.allow.iter().any(|p| p.matches(&path_str)) {
+        return Err(NOT_PERMITTED.to_string());
+    }
+
+    Ok(canonical)
+}
+
+// ファイルを直接開いたとき(CLI引数/ダブルクリック/二重起動)、その親ディレクトリを
+// 許可ルートに加える。開いた操作自体がユーザーの同意なので、初回でもブロックしない。
+fn allow_file_parent(scope: &FsScope, path: &str) {
+    let p = std::path::Path::new(path);
+    // 保存ダイアログで選んだ新規ファイルはまだ存在しないので、その場合は親ディレクトリを正規化する
+    let parent = match std::fs::canonicalize(p) {
+        Ok(canonical) => canonical.parent().map(|d| d.to_path_buf()),
+        Err(_) => p.parent().and_then(|d| std::fs::canonicalize(d).ok()),
+    };
+    if let Some(parent) = parent {
+        let mut inner = scope.0.lock().unwrap();
+        if !inner.roots.contains(&parent) {
+            inner.roots.push(parent);
+        }
+    }
+}
+
+// 監視ハンドルと、自前の保存中にイベントを無視するための抑制期限をまとめて持つ。
+// suppress_until はコールバックとも共有する (Arc) ので、保存中に来たイベントを握り潰せる。
+struct WatchEntry {
+    // マップに保持している間だけ監視が続く。drop(remove)で監視が止まるので読み出しはしない。
+    #[allow(dead_code)]
+    watcher: RecommendedWatcher,
+    suppress_until: Arc<Mutex<Option<Instant>>>,
+}
+
+// 開いているファイルごとの監視ハンドルを保持する。
+// パスをキーにし、unwatch時はWatcherをdropすることで監視を止める。
+struct FileWatchers(Mutex<HashMap<PathBuf, WatchEntry>>);
+
+// 指定パスの監視を (再)設定して管理マップへ登録する。
+// 既存の監視があれば置き換える — atomic rename 後に新しい inode を追い直すためにも使う。
+fn install_watch(target: PathBuf, app: &AppHandle, watchers: &FileWatchers) -> Result<(), String> {
+    let app_handle = app.clone();
+    let emit_path = target.to_string_lossy().into_owned();
+    let suppress_until: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
+    let cb_suppress = suppress_until.clone();
+    // file-changed のデバウンス用。file-removed は取りこぼすと致命的なので間引かない。
+    let mut last_change = Instant::now()
+        .checked_sub(Duration::from_millis(200))
+        .unwrap_or_else(Instant::now);
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        let Ok(event) = res else { return };
+        // 自前の保存(temp書き込み→rename)で起きたイベントは誤検知なので握り潰す
+        if let Some(until) = *cb_suppress.lock().unwrap() {
+            if Instant::now() < until {
+                return;
+            }
+        }
+        match event.kind {
+            EventKind::Remove(_) => {
+                // 削除は必ず通知する (デバウンスで落とさない)
+                if let Some(window) = app_handle.get_webview_window("main") {
+                    let _ = window.emit("file-removed", &emit_path);
+                }
+            }
+            EventKind::Modify(_) | EventKind::Create(_) => {
+                // 大量保存時のイベント嵐を抑えるため、変更は200msで間引く
+                let now = Instant::now();
+                if now.duration_since(last_change) < Duration::from_millis(200) {
+                    return;
+                }
+                last_change = now;
+                if let Some(window) = app_handle.get_webview_window("main") {
+                    let _ = window.emit("file-changed", &emit_path);
+                }
+            }
+            _ => {}
+        }
+    })
+    .map_err(|e| e.to_string())?;
+
+    watcher
+        .watch(&target, RecursiveMode::NonRecursive)
+        .map_err(|e| e.to_string())?;
+
+    watchers
+        .0
+        .lock()
+        .unwrap()
+        .insert(target, WatchEntry { watcher, suppress_until });
+    Ok(())
+}
+
 // Mutexでラップして、スレッドセーフにする
 struct InitialFile(Mutex<Option<String>>);
 // 2回目に開かれたファイルパスを保持するための状態
@@ -49,8 +261,58 @@ async fn force_close_app(app: AppHandle) {
     app.exit(0);
 }
 
+// 開いているファイルが別プログラムに変更/削除されたらフロントエンドに知らせる。
+// 他のパスコマンドと同じく FsScope で許可されたパスのみ監視する。
+#[tauri::command]
+fn watch_file(
+    path: String,
+    app: AppHandle,
+    scope: State<FsScope>,
+    watchers: State<FileWatchers>,
+) -> Result<(), String> {
+    let target = resolve_in_scope(&scope, &path, true)?;
+    install_watch(target, &app, &watchers)
+}
+
+#[tauri::command]
+fn unwatch_file(path: String, scope: State<FsScope>, watchers: State<FileWatchers>) -> Result<(), String> {
+    // キーは正規化後のパス。スコープ外なら監視しているはずもないので無視でよい
+    if let Ok(target) = resolve_in_scope(&scope, &path, true) {
+        // WatchEntryをマップから取り除いてdropすると監視が止まる
+        watchers.0.lock().unwrap().remove(&target);
+    }
+    Ok(())
+}
+
+// UIがフォルダを開いたときにそのルートを許可するためのコマンド
+#[tauri::command]
+fn add_allowed_path(path: String, scope: State<FsScope>) -> Result<(), String> {
+    let canonical = std::fs::canonicalize(&path).map_err(|e| e.to_string())?;
+    let mut inner = scope.0.lock().unwrap();
+    if !inner.roots.contains(&canonical) {
+        inner.roots.push(canonical);
+    }
+    Ok(())
+}
+
 #[tauri::command]
-async fn list_files(dir_path: String) -> Result<Vec<FileEntry>, String> {
+fn list_allowed_paths(scope: State<FsScope>) -> Vec<PathBuf> {
+    scope.0.lock().unwrap().roots.clone()
+}
+
+#[tauri::command]
+async fn list_files(
+    dir_path: String,
+    // "name" / "size" / "modified"。未指定ならソートしない
+    sort_by: Option<String>,
+    // "asc" / "desc"。未指定は昇順
+    direction: Option<String>,
+    // true で隠しファイル(ドットファイル)も含める。デフォルトは除外
+    show_hidden: Option<bool>,
+    scope: State<'_, FsScope>,
+) -> Result<Vec<FileEntry>, String> {
+    let dir_path = resolve_in_scope(&scope, &dir_path, true)?;
+    let show_hidden = show_hidden.unwrap_or(false);
     let mut entries = Vec::new();
     let read_dir = match fs::read_dir(dir_path) {
         Ok(reader) => reader,
@@ -62,60 +324,317 @@ async fn list_files(dir_path: String) -> Result<Vec<FileEntry>, String> {
             let path = entry.path();
             let name = entry.file_name().into_string().unwrap_or_default();
 
-            // .gitや.vscodeのような隠しディレクトリ/ファイルは除外する (オプション)
-            if !name.starts_with('.') {
-                entries.push(FileEntry {
-                    name,
-                    is_dir: path.is_dir(),
-                    path,
-                });
+            // .gitや.vscodeのような隠しディレクトリ/ファイルは除外する (フラグで制御)
+            if !show_hidden && name.starts_with('.') {
+                continue;
             }
+
+            // symlink自体の情報を見たい。DirEntry::metadata はリンクを辿らないのでこれでよい
+            let meta = entry.metadata();
+            let is_symlink = meta
+                .as_ref()
+                .map(|m| m.file_type().is_symlink())
+                .unwrap_or(false);
+            let is_dir = path.is_dir();
+
+            let (size, created, modified, accessed) = match &meta {
+                Ok(m) => (
+                    m.len(),
+                    to_unix_millis(m.created()),
+                    to_unix_millis(m.modified()),
+                    to_unix_millis(m.accessed()),
+                ),
+                Err(_) => (0, None, None, None),
+            };
+
+            // ディレクトリのみ、直下の項目数を数える
+            let child_count = if is_dir {
+                fs::read_dir(&path).ok().map(|r| r.count())
+            } else {
+                None
+            };
+
+            entries.push(FileEntry {
+                name,
+                is_dir,
+                path,
+                size,
+                is_symlink,
+                created,
+                modified,
+                accessed,
+                child_count,
+            });
         }
     }
+
+    // Rust側で並べ替えてから返す。フロントエンドのソート負荷を減らす
+    if let Some(key) = sort_by.as_deref() {
+        entries.sort_by(|a, b| match key {
+            "size" => a.size.cmp(&b.size),
+            "modified" => a.modified.cmp(&b.modified),
+            // 既定は名前順。大文字小文字を無視した辞書順で並べる (数字の自然順ソートはしない)
+            _ => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+        });
+        if direction.as_deref() == Some("desc") {
+            entries.reverse();
+        }
+    }
+
     Ok(entries)
 }
 
 #[tauri::command]
-async fn read_file(path: String) -> Result<FileData, String> {
+async fn read_file(path: String, scope: State<'_, FsScope>) -> Result<FileData, String> {
+    let path = resolve_in_scope(&scope, &path, true)?;
     let bytes = std::fs::read(&path).map_err(|e| e.to_string())?;
 
-    // 1. BOM付きUTF-8のチェック
-    if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
-        let content = std::str::from_utf8(&bytes[3..]).map_err(|e| e.to_string())?.to_string();
-        let line_ending = if content.contains("\r\n") { "CRLF" } else { "LF" };
-        return Ok(FileData { content, encoding: "UTF-8".to_string(), line_ending: line_ending.to_string() });
+    // 無理やり開いてデータ破壊するリスクを避けるため、判定は共通ラダーに委ねる
+    let (content, encoding) = decode_with_ladder(&bytes)?;
+    let line_ending = if content.contains("\r\n") { "CRLF" } else { "LF" };
+    Ok(FileData { content, encoding, line_ending: line_ending.to_string() })
+}
+
+#[tauri::command]
+async fn read_file_range(
+    path: String,
+    start_line: usize,
+    line_count: usize,
+    scope: State<'_, FsScope>,
+) -> Result<FileRangeData, String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let path = resolve_in_scope(&scope, &path, true)?;
+    let file = std::fs::File::open(&path).map_err(|e| e.to_string())?;
+    let mut reader = std::io::BufReader::new(file);
+
+    // ウィンドウ読みは行単位でバイト列を切り出すため、1バイト=1コードユニットではない
+    // UTF-16 や、文字集合切替のエスケープ状態を持つ ISO-2022-JP では成立しない。
+    // b'\n' 走査だと UTF-16LE の改行 0x0A 0x00 の途中で切れてオフセットが1バイトずれ、
+    // ISO-2022-JP はウィンドウ先頭で切替状態を失って 0x21–0x7E が ASCII として化ける。
+    // いずれも read_file(全文)に回してもらい、ここでは先頭を覗いて明示的に弾く。
+    let mut sniff = vec![0u8; 64 * 1024];
+    let sniff_len = reader.read(&mut sniff).map_err(|e| e.to_string())?;
+    let sniff = &sniff[..sniff_len];
+    if sniff.starts_with(&[0xFF, 0xFE]) || sniff.starts_with(&[0xFE, 0xFF]) {
+        return Err("read_file_range does not support UTF-16; open this file with read_file".to_string());
     }
+    // ISO-2022-JP の文字集合指示子 (ESC '$' … / ESC '(' …)。ANSI端末の ESC '[' とは区別する
+    if sniff
+        .windows(2)
+        .any(|w| w[0] == 0x1B && (w[1] == b'$' || w[1] == b'('))
+    {
+        return Err("read_file_range does not support ISO-2022-JP; open this file with read_file".to_string());
+    }
+    reader.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
 
-    // 2. BOMなしUTF-8のチェック (encoding_rsを使用)
-    let (cow, _encoding_used, had_errors) = UTF_8.decode(&bytes);
-    if !had_errors {
-        let content = cow.into_owned();
-        let line_ending = if content.contains("\r\n") { "CRLF" } else { "LF" };
-        return Ok(FileData { content, encoding: "UTF-8".to_string(), line_ending: line_ending.to_string() });
+    // --- 1パス目: 改行をmemchrで走査し、総行数とウィンドウ境界のバイト位置を得る ---
+    // ウィンドウ境界は常に改行(0x0A)直後に揃うので、Shift_JIS/EUC-JP の多バイト文字が
+    // 境界をまたぐことはない(0x0A はどのリードバイト範囲にも入らない)。よって読み足しは不要。
+    // ファイル全体をデコードせず、バイトを読むだけなので巨大ファイルでも軽い
+    let mut buf = [0u8; 64 * 1024];
+    let mut byte_pos: u64 = 0; // ファイル先頭からのバイト位置
+    let mut newlines: usize = 0; // これまでに見た改行の数
+    let end_line = start_line.saturating_add(line_count);
+    // start_line == 0 のウィンドウは先頭から始まる
+    let mut start_offset: Option<u64> = if start_line == 0 { Some(0) } else { None };
+    let mut end_offset: Option<u64> = None;
+    let mut saw_any = false;
+    let mut last_byte_newline = true;
+
+    loop {
+        let n = reader.read(&mut buf).map_err(|e| e.to_string())?;
+        if n == 0 {
+            break;
+        }
+        saw_any = true;
+        for i in memchr::memchr_iter(b'\n', &buf[..n]) {
+            let next_line_start = byte_pos + i as u64 + 1;
+            newlines += 1;
+            if newlines == start_line {
+                start_offset = Some(next_line_start);
+            }
+            if newlines == end_line {
+                end_offset = Some(next_line_start);
+            }
+        }
+        last_byte_newline = buf[n - 1] == b'\n';
+        byte_pos += n as u64;
     }
 
-    // 3. Shift_JISのチェック
-    let (cow, _encoding_used, had_errors) = SHIFT_JIS.decode(&bytes);
-    if !had_errors {
-        let content = cow.into_owned();
-        let line_ending = if content.contains("\r\n") { "CRLF" } else { "LF" };
-        return Ok(FileData { content, encoding: "Shift_JIS".to_string(), line_ending: line_ending.to_string() });
+    let total_lines = if !saw_any {
+        0
+    } else if last_byte_newline {
+        newlines
+    } else {
+        newlines + 1
+    };
+
+    // start_lineがファイル末尾を越えている場合は空のウィンドウを返す
+    let start_offset = match start_offset {
+        Some(off) => off,
+        None => {
+            return Ok(FileRangeData {
+                content: String::new(),
+                encoding: "UTF-8".to_string(),
+                line_ending: "LF".to_string(),
+                total_lines,
+                byte_offset: byte_pos,
+            });
+        }
+    };
+    // line_count == 0 は空ウィンドウ要求。start_line == 0 だと end_line == 0 となり改行一致
+    // 判定が発火しないため end_offset がEOFに落ち、2パス目で全文を読んでしまう。空で返す
+    if line_count == 0 {
+        return Ok(FileRangeData {
+            content: String::new(),
+            encoding: "UTF-8".to_string(),
+            line_ending: "LF".to_string(),
+            total_lines,
+            byte_offset: start_offset,
+        });
     }
+    // 範囲がEOFまで伸びる場合はファイル末尾をウィンドウの終端とする
+    let end_offset = end_offset.unwrap_or(byte_pos);
 
-    // 4. ★★★ それ以外はエラーとして弾く ★★★
-    // 無理やり開いてデータ破壊するリスクを避ける
-    Err("Unsupported encoding detected. MirrorShard only supports UTF-8 and Shift_JIS.".to_string())
+    // --- 2パス目: 必要なバイトだけを読み出す ---
+    reader
+        .seek(SeekFrom::Start(start_offset))
+        .map_err(|e| e.to_string())?;
+    let window_len = end_offset.saturating_sub(start_offset) as usize;
+    let mut bytes = vec![0u8; window_len];
+    reader.read_exact(&mut bytes).map_err(|e| e.to_string())?;
+
+    // --- read_file と同じ判定ラダーで、読んだ分だけをデコードする ---
+    // ウィンドウは行単位で揃っているため多バイト文字が境界をまたがず、そのまま掛けてよい。
+    let (content, encoding) = decode_with_ladder(&bytes)?;
+
+    let line_ending = if content.contains("\r\n") { "CRLF" } else { "LF" };
+    Ok(FileRangeData {
+        content,
+        encoding,
+        line_ending: line_ending.to_string(),
+        total_lines,
+        byte_offset: start_offset,
+    })
 }
 
 #[tauri::command]
-async fn write_file(path: String, content: String, encoding: String) -> Result<(), String> {
-    let bytes = if encoding == "Shift_JIS" {
-        let (cow, _encoding_used, _had_errors) = SHIFT_JIS.encode(&content);
-        cow.into_owned()
-    } else {
-        content.into_bytes() // UTF-8として扱う
+async fn write_file(
+    path: String,
+    content: String,
+    encoding: String,
+    // true のとき、上書き前の内容を `<path>.bak` として残す
+    backup: Option<bool>,
+    app: AppHandle,
+    scope: State<'_, FsScope>,
+    watchers: State<'_, FileWatchers>,
+) -> Result<(), String> {
+    use std::io::Write;
+
+    let path = resolve_in_scope(&scope, &path, false)?;
+
+    // このファイルを監視中なら、atomic rename による IN_DELETE_SELF が自前の保存を
+    // 「外部からの削除」と誤検知しないよう抑制する。抑制期限は保存完了(rename)直前に
+    // 張る — バックアップ copy や巨大ファイルの write_all/sync_all が1秒を超えても、
+    // 肝心の rename イベントが抑制の切れた後に飛んで誤検知にならないようにするため。
+    let suppress = {
+        let map = watchers.0.lock().unwrap();
+        map.get(&path).map(|entry| entry.suppress_until.clone())
     };
-    std::fs::write(path, bytes).map_err(|e| e.to_string())
+    let was_watched = suppress.is_some();
+    // read_file が返すラベル(EUC-JP / ISO-2022-JP / Shift_JIS 等)をそのまま対応する
+    // エンコーダに流し、開いたときの文字コードで round-trip させる。
+    // encoding_rs は UTF-16 の *エンコーダ* を持たない (encode すると UTF-8 に落ちる) ので、
+    // UTF-16LE/BE は BOM 付きで手動エンコードする。未知のラベルは UTF-8 として書き出す。
+    let bytes = match encoding.as_str() {
+        "UTF-16LE" => {
+            let mut out = vec![0xFF, 0xFE];
+            for u in content.encode_utf16() {
+                out.extend_from_slice(&u.to_le_bytes());
+            }
+            out
+        }
+        "UTF-16BE" => {
+            let mut out = vec![0xFE, 0xFF];
+            for u in content.encode_utf16() {
+                out.extend_from_slice(&u.to_be_bytes());
+            }
+            out
+        }
+        _ => match encoding_rs::Encoding::for_label(encoding.as_bytes()) {
+            Some(enc) => {
+                let (cow, _encoding_used, _had_errors) = enc.encode(&content);
+                cow.into_owned()
+            }
+            None => content.into_bytes(),
+        },
+    };
+
+    // クラッシュやディスクフルで元ファイルを切り詰めないよう、
+    // 同じディレクトリの一時ファイルに書いてfsyncし、atomicにrenameで差し替える。
+    let parent = path
+        .parent()
+        .ok_or_else(|| "Invalid target path".to_string())?;
+
+    // 上書き前の内容を .bak として退避する (任意)
+    if backup.unwrap_or(false) && path.exists() {
+        let mut bak = path.clone().into_os_string();
+        bak.push(".bak");
+        std::fs::copy(&path, PathBuf::from(bak)).map_err(|e| e.to_string())?;
+    }
+
+    // 元ファイルのパーミッションと更新日時(mtime)を引き継ぐために控えておく
+    let original_meta = std::fs::metadata(&path).ok();
+    let original_perms = original_meta.as_ref().map(|m| m.permissions());
+    let original_mtime = original_meta
+        .as_ref()
+        .map(filetime::FileTime::from_last_modification_time);
+
+    // 同一ボリューム上でなければrenameがatomicにならないので、必ず同じ親ディレクトリに作る
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp_path = parent.join(format!(".{}.{}.tmp", file_name, std::process::id()));
+
+    let mut tmp = std::fs::File::create(&tmp_path).map_err(|e| {
+        format!(
+            "Could not create temporary file in the target directory (needed for an atomic replace): {}",
+            e
+        )
+    })?;
+    // 途中で失敗した場合に一時ファイルを必ず片付けるためのクロージャ
+    let cleanup = |msg: String| {
+        let _ = std::fs::remove_file(&tmp_path);
+        msg
+    };
+
+    tmp.write_all(&bytes).map_err(|e| cleanup(e.to_string()))?;
+    // ディスクへ確実に書き出してからrenameする
+    tmp.sync_all().map_err(|e| cleanup(e.to_string()))?;
+    if let Some(perms) = original_perms {
+        // 元ファイルが存在した場合のみ、そのパーミッションを引き継ぐ (新規ファイルは既定のまま)
+        tmp.set_permissions(perms).map_err(|e| cleanup(e.to_string()))?;
+    }
+    drop(tmp);
+
+    // renameで更新日時が変わらないよう、元のmtimeを一時ファイルへ復元してから差し替える
+    if let Some(mtime) = original_mtime {
+        let _ = filetime::set_file_mtime(&tmp_path, mtime);
+    }
+
+    // rename の直前で抑制期限を張り直す。ここからイベント発火〜貼り直しまではミリ秒単位
+    if let Some(suppress) = &suppress {
+        *suppress.lock().unwrap() = Some(Instant::now() + Duration::from_secs(1));
+    }
+    std::fs::rename(&tmp_path, &path).map_err(|e| cleanup(e.to_string()))?;
+
+    // renameで元のinodeは外れるので、監視していたなら新しいinodeへ貼り直す
+    if was_watched {
+        let _ = install_watch(path.clone(), &app, &watchers);
+    }
+    Ok(())
 }
 
 #[tauri::command]
@@ -126,6 +645,10 @@ async fn save_file_as(app: tauri::AppHandle, content: String) {
         .set_file_name("Untitled.txt")
         .save_file(move |file_path| {
             if let Some(path) = file_path {
+                // ネイティブ保存ダイアログで選んだパスはユーザーの同意そのものなので、
+                // スコープ検証は行わない。代わりにそのフォルダを以後の許可ルートに加える。
+                let scope = app.state::<FsScope>();
+                allow_file_parent(&scope, &path.to_string());
                 if let Err(e) = std::fs::write(path.to_string(), &content) {
                     eprintln!("Failed to save file: {}", e.to_string());
                     app.dialog()
@@ -143,7 +666,39 @@ pub fn run() {
         .plugin(tauri_plugin_cli::init())
         .manage(InitialFile(Mutex::new(None))) // 最初の起動用
         .manage(SecondInstanceFile(Mutex::new(None))) // 2回目以降の起動用
+        .manage(FsScope(Mutex::new(FsScopeInner::default()))) // ファイルアクセス制限
+        .manage(FileWatchers(Mutex::new(HashMap::new()))) // 外部変更の監視
         .setup(|app| {
+            // --- 永続化されたスコープ設定をstoreから読み込む ---
+            // "fs-scope.json" の allowedRoots / allow / deny を初期スコープとして採用する。
+            if let Ok(store) = app.store("fs-scope.json") {
+                let scope: State<FsScope> = app.state();
+                let mut inner = scope.0.lock().unwrap();
+                if let Some(roots) = store.get("allowedRoots") {
+                    if let Some(arr) = roots.as_array() {
+                        for v in arr {
+                            if let Some(s) = v.as_str() {
+                                if let Ok(p) = std::fs::canonicalize(s) {
+                                    inner.roots.push(p);
+                                }
+                            }
+                        }
+                    }
+                }
+                let load_patterns = |key: &str| -> Vec<glob::Pattern> {
+                    store
+                        .get(key)
+                        .and_then(|v| v.as_array().cloned())
+                        .unwrap_or_default()
+                        .iter()
+                        .filter_map(|v| v.as_str())
+                        .filter_map(|s| glob::Pattern::new(s).ok())
+                        .collect()
+                };
+                inner.allow = load_patterns("allow");
+                inner.deny = load_patterns("deny");
+            }
+
             // ---  起動時引数を解析し、状態に書き込む ---
             if let Ok(matches) = app.cli().matches() {
                 if let Some(path_arg) = matches.args.get("filePath") {
@@ -151,6 +706,9 @@ pub fn run() {
                         // State<InitialFile> を使って、管理下の状態にアクセス
                         let state: State<InitialFile> = app.state();
                         *state.0.lock().unwrap() = Some(path.to_string());
+                        // ダブルクリック/関連付けで開いたファイルのフォルダを許可する
+                        let scope: State<FsScope> = app.state();
+                        allow_file_parent(&scope, path);
                     }
                 }
             }
@@ -162,6 +720,9 @@ pub fn run() {
                 // ★イベントを送るのではなく、状態にパスを書き込む
                 let state: State<SecondInstanceFile> = app.state();
                 *state.0.lock().unwrap() = Some(path.clone());
+                // 二重起動で開いたファイルのフォルダも許可する
+                let scope: State<FsScope> = app.state();
+                allow_file_parent(&scope, path);
             }
             // 既存のウィンドウにフォーカスを当てる
             if let Some(window) = app.get_webview_window("main") {
@@ -195,11 +756,16 @@ pub fn run() {
             // ★★★ すべてのコマンドをここに登録 ★★★
             list_files,
             read_file,
+            read_file_range,
             write_file,
             save_file_as,
             force_close_app,
             get_initial_file,
-            get_second_instance_file
+            get_second_instance_file,
+            add_allowed_path,
+            list_allowed_paths,
+            watch_file,
+            unwatch_file
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");